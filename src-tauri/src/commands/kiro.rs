@@ -20,14 +20,14 @@ pub fn delete_kiro_accounts(account_ids: Vec<String>) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn import_kiro_from_json(json_content: String) -> Result<Vec<KiroAccount>, String> {
-    kiro_account::import_from_json(&json_content)
+pub async fn import_kiro_from_json(json_content: String) -> Result<Vec<KiroAccount>, String> {
+    kiro_account::import_from_json(&json_content).await
 }
 
 #[tauri::command]
 pub async fn import_kiro_from_local() -> Result<Vec<KiroAccount>, String> {
     let payload = kiro_oauth::build_payload_from_local_files()?;
-    let payload = kiro_oauth::enrich_payload_with_runtime_usage(payload).await;
+    let payload = kiro_oauth::enrich_payload_with_runtime_usage_timeout(payload).await;
     let account = kiro_account::upsert_account(payload)?;
     Ok(vec![account])
 }
@@ -68,4 +68,38 @@ pub fn file_corrupted_error(file_name: &str, file_path: &str, original_error: &s
     .to_string()
 }
 
+/// 创建 Kiro 本地授权文件缺失错误的辅助函数（前端据此引导改用 Token 导入）
+pub fn kiro_local_auth_not_found_error(file_path: &str) -> String {
+    serde_json::json!({
+        "error_type": "kiro_local_auth_not_found",
+        "file_path": file_path,
+        "message": format!("Kiro login info not found on this machine ({})", file_path)
+    })
+    .to_string()
+}
+
+/// 创建 Kiro 登录需用户后续操作的错误（`next_action` 供前端引导下一步）
+pub fn kiro_login_action_required_error(
+    login_option: &str,
+    next_action: &str,
+    message: &str,
+) -> String {
+    serde_json::json!({
+        "error_type": "kiro_login_action_required",
+        "login_option": login_option,
+        "next_action": next_action,
+        "message": message
+    })
+    .to_string()
+}
+
+/// 创建 Kiro refresh token 失效、需重新登录的错误
+pub fn kiro_reauth_required_error(message: &str) -> String {
+    serde_json::json!({
+        "error_type": "kiro_reauth_required",
+        "message": message
+    })
+    .to_string()
+}
+
 pub type AppResult<T> = Result<T, AppError>;
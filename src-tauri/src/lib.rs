@@ -73,6 +73,9 @@ pub fn run() {
                 modules::websocket::start_server().await;
             });
 
+            // 恢复重启前未完成的 Kiro 登录会话
+            modules::kiro_oauth::restore_pending_login();
+
             // 初始化系统托盘
             if let Err(e) = modules::tray::create_tray(app.handle()) {
                 logger::log_error(&format!("[Tray] Failed to create system tray: {}", e));
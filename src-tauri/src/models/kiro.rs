@@ -28,6 +28,9 @@ pub struct KiroAccount {
     pub issuer_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_id: Option<String>,
+    // OIDC 客户端密钥：仅用于 refresh，不写入授权快照，导出时清除。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub scopes: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -136,6 +139,7 @@ pub struct KiroOAuthCompletePayload {
     pub idc_region: Option<String>,
     pub issuer_url: Option<String>,
     pub client_id: Option<String>,
+    pub client_secret: Option<String>,
     pub scopes: Option<String>,
     pub login_hint: Option<String>,
 
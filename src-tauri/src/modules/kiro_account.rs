@@ -438,6 +438,7 @@ fn merge_duplicate_account(primary: &mut KiroAccount, duplicate: &KiroAccount) {
     fill_if_none(&mut primary.idc_region, &duplicate.idc_region);
     fill_if_none(&mut primary.issuer_url, &duplicate.issuer_url);
     fill_if_none(&mut primary.client_id, &duplicate.client_id);
+    fill_if_none(&mut primary.client_secret, &duplicate.client_secret);
     fill_if_none(&mut primary.scopes, &duplicate.scopes);
     fill_if_none(&mut primary.login_hint, &duplicate.login_hint);
     fill_if_none(&mut primary.plan_name, &duplicate.plan_name);
@@ -639,6 +640,10 @@ fn apply_payload(account: &mut KiroAccount, payload: KiroOAuthCompletePayload) {
     account.expires_at = payload.expires_at;
     account.idc_region = payload.idc_region;
     account.issuer_url = payload.issuer_url;
+    // 导入的快照不带密钥时保留已有密钥，除非 clientId 已变化
+    if payload.client_secret.is_some() || account.client_id != payload.client_id {
+        account.client_secret = payload.client_secret;
+    }
     account.client_id = payload.client_id;
     account.scopes = payload.scopes;
     account.login_hint = payload.login_hint;
@@ -719,6 +724,7 @@ pub fn upsert_account(payload: KiroOAuthCompletePayload) -> Result<KiroAccount,
         idc_region: payload.idc_region.clone(),
         issuer_url: payload.issuer_url.clone(),
         client_id: payload.client_id.clone(),
+        client_secret: payload.client_secret.clone(),
         scopes: payload.scopes.clone(),
         login_hint: payload.login_hint.clone(),
         plan_name: payload.plan_name.clone(),
@@ -755,6 +761,15 @@ pub fn upsert_account(payload: KiroOAuthCompletePayload) -> Result<KiroAccount,
 }
 
 pub async fn refresh_account_token(account_id: &str) -> Result<KiroAccount, String> {
+    let current_account_id = resolve_current_account_id(&list_accounts());
+    refresh_account_token_with_current(account_id, current_account_id.as_deref()).await
+}
+
+/// 当前/绑定账号与 Kiro 客户端共用 refresh token，不做远程 refresh
+async fn refresh_account_token_with_current(
+    account_id: &str,
+    current_account_id: Option<&str>,
+) -> Result<KiroAccount, String> {
     let started_at = Instant::now();
     let mut account = load_account(account_id).ok_or_else(|| "账号不存在".to_string())?;
     logger::log_info(&format!(
@@ -762,7 +777,8 @@ pub async fn refresh_account_token(account_id: &str) -> Result<KiroAccount, Stri
         account.id, account.email
     ));
 
-    let payload = kiro_oauth::refresh_payload_for_account(&account).await?;
+    let is_current = current_account_id == Some(account.id.as_str());
+    let payload = kiro_oauth::refresh_payload_for_account(&account, !is_current).await?;
     let tags = account.tags.clone();
     let created_at = account.created_at;
     apply_payload(&mut account, payload);
@@ -786,9 +802,10 @@ pub async fn refresh_all_tokens() -> Result<Vec<(String, Result<KiroAccount, Str
     use std::sync::Arc;
     use tokio::sync::Semaphore;
 
-    const MAX_CONCURRENT: usize = 5;
     let accounts = list_accounts();
     let total = accounts.len();
+    // 刷新会更新 last_used，先确定当前账号，避免批量过程中被其他账号顶替
+    let current_account_id = resolve_current_account_id(&accounts);
     let active_accounts: Vec<KiroAccount> = accounts
         .into_iter()
         .filter(|account| !is_banned_account(account))
@@ -801,18 +818,20 @@ pub async fn refresh_all_tokens() -> Result<Vec<(String, Result<KiroAccount, Str
         ));
     }
 
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT));
+    let semaphore = Arc::new(Semaphore::new(kiro_oauth::KIRO_RUNTIME_MAX_CONCURRENT));
     let tasks: Vec<_> = active_accounts
         .into_iter()
         .map(|account| {
             let id = account.id;
             let semaphore = semaphore.clone();
+            let current_account_id = current_account_id.clone();
             async move {
                 let _permit = semaphore
                     .acquire_owned()
                     .await
                     .map_err(|e| format!("获取 Kiro 刷新并发许可失败: {}", e))?;
-                let result = refresh_account_token(&id).await;
+                let result =
+                    refresh_account_token_with_current(&id, current_account_id.as_deref()).await;
                 Ok::<(String, Result<KiroAccount, String>), String>((id, result))
             }
         })
@@ -1036,7 +1055,7 @@ fn payloads_from_import_json_value(value: Value) -> Result<Vec<KiroOAuthComplete
     }
 }
 
-pub fn import_from_json(json_content: &str) -> Result<Vec<KiroAccount>, String> {
+pub async fn import_from_json(json_content: &str) -> Result<Vec<KiroAccount>, String> {
     if let Ok(account) = serde_json::from_str::<KiroAccount>(json_content) {
         let saved = upsert_account_record(account)?;
         return Ok(vec![saved]);
@@ -1053,6 +1072,7 @@ pub fn import_from_json(json_content: &str) -> Result<Vec<KiroAccount>, String>
 
     if let Ok(value) = serde_json::from_str::<Value>(json_content) {
         if let Ok(payloads) = payloads_from_import_json_value(value) {
+            let payloads = kiro_oauth::refresh_imported_payloads(payloads).await;
             let mut result = Vec::with_capacity(payloads.len());
             for payload in payloads {
                 let saved = upsert_account(payload)?;
@@ -1069,6 +1089,17 @@ pub fn export_accounts(account_ids: &[String]) -> Result<String, String> {
     let accounts: Vec<KiroAccount> = account_ids
         .iter()
         .filter_map(|id| load_account(id))
+        .map(|mut account| {
+            account.client_secret = None;
+            if let Some(obj) = account
+                .kiro_auth_token_raw
+                .as_mut()
+                .and_then(Value::as_object_mut)
+            {
+                obj.remove("clientSecret");
+            }
+            account
+        })
         .collect();
     serde_json::to_string_pretty(&accounts).map_err(|e| format!("序列化失败: {}", e))
 }
@@ -1198,6 +1198,8 @@ fn write_local_auth_token_file(account: &KiroAccount) -> Result<(), String> {
     let provider = account_provider(account);
     let auth_method = account_auth_method(account, provider.as_deref());
     let profile_arn = account_profile_arn(account);
+    // 早期账号的快照里可能带有 OIDC clientSecret，不写入 Kiro 客户端缓存
+    obj.remove("clientSecret");
     obj.insert(
         "accessToken".to_string(),
         Value::String(account.access_token.clone()),
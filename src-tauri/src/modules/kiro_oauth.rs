@@ -1,24 +1,35 @@
 use base64::Engine;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use crate::models::kiro::{
     KiroAccount, KiroOAuthCompletePayload, KiroOAuthStartOptions, KiroOAuthStartResponse,
 };
-use crate::modules::{kiro_account, logger};
+use crate::modules::{account, kiro_account, logger};
 
 const KIRO_AUTH_PORTAL_URL: &str = "https://app.kiro.dev/signin";
 const KIRO_TOKEN_ENDPOINT: &str = "https://prod.us-east-1.auth.desktop.kiro.dev/oauth/token";
+const KIRO_REFRESH_TOKEN_ENDPOINT: &str =
+    "https://prod.us-east-1.auth.desktop.kiro.dev/refreshToken";
+const TOKEN_REFRESH_SKEW_SECONDS: i64 = 300;
 const KIRO_RUNTIME_DEFAULT_ENDPOINT: &str = "https://q.us-east-1.amazonaws.com";
 const KIRO_ACCOUNT_STATUS_NORMAL: &str = "normal";
 const KIRO_ACCOUNT_STATUS_BANNED: &str = "banned";
 const KIRO_ACCOUNT_STATUS_ERROR: &str = "error";
 const OAUTH_TIMEOUT_SECONDS: u64 = 600;
 const OAUTH_POLL_INTERVAL_MS: u64 = 250;
+const RUNTIME_USAGE_TIMEOUT_SECONDS: u64 = 20;
+/// Kiro runtime 请求（批量刷新/导入补全）的最大并发数
+pub(crate) const KIRO_RUNTIME_MAX_CONCURRENT: usize = 5;
 const BUILDER_ID_START_URL: &str = "https://view.awsapps.com/start";
+const PENDING_OAUTH_FILE: &str = "kiro_oauth_pending.json";
+const OIDC_DISCOVERY_TIMEOUT_SECONDS: u64 = 10;
 const CALLBACK_PORT_CANDIDATES: [u16; 10] = [
     3128, 4649, 6588, 8008, 9091, 49153, 50153, 51153, 52153, 53153,
 ];
@@ -36,7 +47,7 @@ struct OAuthCallbackData {
     audience: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct PendingOAuthState {
     flow: OAuthFlow,
     login_id: String,
@@ -52,10 +63,11 @@ struct PendingOAuthState {
     client_secret: Option<String>,
     device_code: Option<String>,
     interval_seconds: u64,
+    #[serde(skip)]
     callback_result: Option<Result<OAuthCallbackData, String>>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 enum OAuthFlow {
     Portal,
     BuilderId,
@@ -405,12 +417,122 @@ fn set_callback_result_for_login(
     if let Ok(mut guard) = PENDING_OAUTH_STATE.lock() {
         if let Some(state) = guard.as_mut() {
             if state.login_id == expected_login_id && state.state_token == expected_state {
+                // 超时/失败的会话不应在重启后被恢复（callback_result 不落盘）
+                if result.is_err() {
+                    clear_persisted_pending_state();
+                }
                 state.callback_result = Some(result);
             }
         }
     }
 }
 
+fn pending_state_path() -> Result<PathBuf, String> {
+    Ok(account::get_data_dir()?.join(PENDING_OAUTH_FILE))
+}
+
+fn persist_pending_state(state: &PendingOAuthState) {
+    let result = pending_state_path().and_then(|path| {
+        let content = serde_json::to_string_pretty(state)
+            .map_err(|e| format!("Failed to serialize pending login: {}", e))?;
+        fs::write(path, content).map_err(|e| format!("Failed to write pending login: {}", e))
+    });
+    if let Err(err) = result {
+        logger::log_warn(&format!(
+            "[Kiro OAuth] Pending login not persisted, it will not survive a restart: {}",
+            err
+        ));
+    }
+}
+
+fn clear_persisted_pending_state() {
+    if let Ok(path) = pending_state_path() {
+        if path.exists() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn set_pending_state(state: &PendingOAuthState) {
+    if let Ok(mut guard) = PENDING_OAUTH_STATE.lock() {
+        *guard = Some(state.clone());
+    }
+    persist_pending_state(state);
+}
+
+fn parse_restorable_pending_state(raw: &str, now: i64) -> Option<PendingOAuthState> {
+    let state = serde_json::from_str::<PendingOAuthState>(raw).ok()?;
+    if state.login_id.trim().is_empty() || state.expires_at <= now {
+        return None;
+    }
+    Some(state)
+}
+
+/// 启动时恢复未过期的登录会话，并重新拉起本地回调服务
+pub fn restore_pending_login() {
+    let Ok(path) = pending_state_path() else {
+        return;
+    };
+    let Ok(raw) = fs::read_to_string(&path) else {
+        return;
+    };
+    let Some(state) = parse_restorable_pending_state(&raw, now_timestamp()) else {
+        clear_persisted_pending_state();
+        return;
+    };
+
+    if let Ok(mut guard) = PENDING_OAUTH_STATE.lock() {
+        if guard.is_some() {
+            return;
+        }
+        *guard = Some(state.clone());
+    }
+
+    logger::log_info(&format!(
+        "[Kiro OAuth] Restored pending login: login_id={}, expires_in={}s",
+        state.login_id,
+        (state.expires_at - now_timestamp()).max(0)
+    ));
+
+    if state.flow == OAuthFlow::BuilderId || state.callback_port == 0 {
+        return;
+    }
+
+    spawn_callback_server(
+        state.callback_port,
+        state.login_id.clone(),
+        state.state_token.clone(),
+        "[Kiro OAuth] Restored callback service error",
+    );
+}
+
+fn spawn_callback_server(
+    callback_port: u16,
+    expected_login_id: String,
+    expected_state: String,
+    log_context: &'static str,
+) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = start_callback_server(
+            callback_port,
+            expected_login_id.clone(),
+            expected_state.clone(),
+        )
+        .await
+        {
+            logger::log_error(&format!(
+                "{}: login_id={}, error={}",
+                log_context, expected_login_id, err
+            ));
+            set_callback_result_for_login(
+                &expected_login_id,
+                &expected_state,
+                Err(format!("Local callback service error: {}", err)),
+            );
+        }
+    });
+}
+
 fn extract_profile_arn_from_payload(payload: &KiroOAuthCompletePayload) -> Option<String> {
     extract_profile_arn(
         payload.kiro_auth_token_raw.as_ref(),
@@ -497,6 +619,7 @@ fn inject_callback_context_into_token(token: &mut Value, callback: &OAuthCallbac
     if !has_expires_at {
         let expires_in_seconds = obj
             .get("expiresIn")
+            .or_else(|| obj.get("expires_in"))
             .and_then(|value| {
                 value
                     .as_i64()
@@ -530,6 +653,76 @@ fn unwrap_token_response(mut response: Value) -> Value {
     }
 }
 
+fn external_idp_fallback_error(login_option: &str) -> String {
+    crate::error::kiro_login_action_required_error(
+        login_option,
+        "open_kiro_client",
+        "Your organization signs in through an external identity provider. Finish signing in with the Kiro client, then import from local Kiro.",
+    )
+}
+
+async fn fetch_oidc_discovery(issuer_url: &str) -> Result<Value, String> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer_url.trim_end_matches('/')
+    );
+    let response = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(
+            OIDC_DISCOVERY_TIMEOUT_SECONDS,
+        ))
+        .build()
+        .map_err(|e| format!("Failed to build OIDC discovery client: {}", e))?
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("OIDC discovery request failed: {}", e))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(format!(
+            "OIDC discovery returned error: status={}",
+            status.as_u16()
+        ));
+    }
+    serde_json::from_str::<Value>(&body)
+        .map_err(|e| format!("Failed to parse OIDC discovery document: {}", e))
+}
+
+/// 从 IdP 发现文档中取出 https 令牌端点
+fn discovered_token_endpoint(discovery: &Value) -> Option<String> {
+    pick_string(Some(discovery), &[&["token_endpoint"]])
+        .filter(|value| value.to_ascii_lowercase().starts_with("https://"))
+}
+
+/// External IdP 回调不带授权码：门户把 IdP 换取交给 Kiro 客户端，这里只探测发现文档用于诊断，
+/// 仍引导用户在 Kiro 客户端完成登录后从本地导入
+async fn external_idp_login_error(
+    login_id: &str,
+    login_option: &str,
+    callback: &OAuthCallbackData,
+) -> String {
+    let probe = match callback
+        .issuer_url
+        .as_deref()
+        .and_then(|value| normalize_non_empty(Some(value)))
+    {
+        Some(issuer_url) => match fetch_oidc_discovery(&issuer_url).await {
+            Ok(discovery) => format!(
+                "issuer_url={}, token_endpoint={}",
+                issuer_url,
+                discovered_token_endpoint(&discovery).unwrap_or_else(|| "<none>".to_string())
+            ),
+            Err(err) => format!("issuer_url={}, discovery_error={}", issuer_url, err),
+        },
+        None => "issuer_url=<none>".to_string(),
+    };
+    logger::log_info(&format!(
+        "[Kiro OAuth] External IdP callback without code: login_id={}, {}",
+        login_id, probe
+    ));
+    external_idp_fallback_error(login_option)
+}
+
 async fn exchange_code_for_token(
     callback: &OAuthCallbackData,
     code_verifier: &str,
@@ -782,7 +975,6 @@ async fn start_callback_server(
 
     let server = Server::http(format!("127.0.0.1:{}", callback_port))
         .map_err(|e| format!("Failed to start Kiro OAuth callback service: {}", e))?;
-    let started = std::time::Instant::now();
 
     logger::log_info(&format!(
         "[Kiro OAuth] Local callback service started: login_id={}, port={}",
@@ -790,22 +982,23 @@ async fn start_callback_server(
     ));
 
     loop {
-        let should_stop = {
+        let (should_stop, expires_at) = {
             let guard = PENDING_OAUTH_STATE
                 .lock()
                 .map_err(|_| "OAuth state lock unavailable".to_string())?;
             match guard.as_ref() {
-                Some(state) => {
-                    state.login_id != expected_login_id || state.state_token != expected_state
-                }
-                None => true,
+                Some(state) => (
+                    state.login_id != expected_login_id || state.state_token != expected_state,
+                    state.expires_at,
+                ),
+                None => (true, 0),
             }
         };
         if should_stop {
             break;
         }
 
-        if started.elapsed().as_secs() > OAUTH_TIMEOUT_SECONDS {
+        if now_timestamp() > expires_at {
             set_callback_result_for_login(
                 &expected_login_id,
                 &expected_state,
@@ -1207,11 +1400,19 @@ fn extract_profile_name(auth_token: Option<&Value>, profile: Option<&Value>) ->
     .or_else(|| pick_string(auth_token, &[&["provider"], &["loginProvider"]]))
 }
 
+/// OIDC clientSecret 不留在授权快照里（快照会原样写入 Kiro 客户端缓存并随导出带出）
+fn take_client_secret(raw: &mut Value) -> Option<String> {
+    raw.as_object_mut()
+        .and_then(|obj| obj.remove("clientSecret"))
+        .and_then(|value| value.as_str().and_then(|v| normalize_non_empty(Some(v))))
+}
+
 pub(crate) fn build_payload_from_snapshot(
-    auth_token: Value,
+    mut auth_token: Value,
     profile: Option<Value>,
     usage: Option<Value>,
 ) -> Result<KiroOAuthCompletePayload, String> {
+    let client_secret = take_client_secret(&mut auth_token);
     let access_token = pick_string(
         Some(&auth_token),
         &[
@@ -1409,6 +1610,7 @@ pub(crate) fn build_payload_from_snapshot(
         idc_region,
         issuer_url,
         client_id,
+        client_secret,
         scopes,
         login_hint,
         plan_name,
@@ -1428,6 +1630,9 @@ pub(crate) fn build_payload_from_snapshot(
 }
 
 pub fn payload_from_account(account: &KiroAccount) -> KiroOAuthCompletePayload {
+    let mut kiro_auth_token_raw = account.kiro_auth_token_raw.clone();
+    // 早期登录曾把 clientSecret 写进快照，读出时迁移到独立字段
+    let legacy_client_secret = kiro_auth_token_raw.as_mut().and_then(take_client_secret);
     KiroOAuthCompletePayload {
         email: account.email.clone(),
         user_id: account.user_id.clone(),
@@ -1439,6 +1644,7 @@ pub fn payload_from_account(account: &KiroAccount) -> KiroOAuthCompletePayload {
         idc_region: account.idc_region.clone(),
         issuer_url: account.issuer_url.clone(),
         client_id: account.client_id.clone(),
+        client_secret: account.client_secret.clone().or(legacy_client_secret),
         scopes: account.scopes.clone(),
         login_hint: account.login_hint.clone(),
         plan_name: account.plan_name.clone(),
@@ -1449,7 +1655,7 @@ pub fn payload_from_account(account: &KiroAccount) -> KiroOAuthCompletePayload {
         bonus_used: account.bonus_used,
         usage_reset_at: account.usage_reset_at,
         bonus_expire_days: account.bonus_expire_days,
-        kiro_auth_token_raw: account.kiro_auth_token_raw.clone(),
+        kiro_auth_token_raw,
         kiro_profile_raw: account.kiro_profile_raw.clone(),
         kiro_usage_raw: account.kiro_usage_raw.clone(),
         status: account.status.clone(),
@@ -1457,9 +1663,13 @@ pub fn payload_from_account(account: &KiroAccount) -> KiroOAuthCompletePayload {
     }
 }
 
+/// 本地授权文件缺失时返回 `kiro_local_auth_not_found` 结构化错误；解析失败仍为普通错误
 pub fn build_payload_from_local_files() -> Result<KiroOAuthCompletePayload, String> {
     let auth_token = kiro_account::read_local_auth_token_json()?.ok_or_else(|| {
-        "Kiro login info not found on this machine (~/.aws/sso/cache/kiro-auth-token.json)".to_string()
+        let path = kiro_account::get_default_kiro_auth_token_path()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_else(|_| "~/.aws/sso/cache/kiro-auth-token.json".to_string());
+        crate::error::kiro_local_auth_not_found_error(&path)
     })?;
     let profile = kiro_account::read_local_profile_json()?;
     let usage = kiro_account::read_local_usage_snapshot()?;
@@ -1635,11 +1845,413 @@ pub async fn enrich_payload_with_runtime_usage(
     payload
 }
 
+async fn run_with_usage_timeout<F>(
+    mut payload: KiroOAuthCompletePayload,
+    enrich: F,
+    timeout: std::time::Duration,
+) -> KiroOAuthCompletePayload
+where
+    F: std::future::Future<Output = KiroOAuthCompletePayload>,
+{
+    match tokio::time::timeout(timeout, enrich).await {
+        Ok(enriched) => enriched,
+        Err(_) => {
+            let reason = format!(
+                "Runtime usage request timed out after {}s",
+                timeout.as_secs()
+            );
+            logger::log_warn(&format!(
+                "[Kiro Import] {}: email={}",
+                reason, payload.email
+            ));
+            set_payload_status(&mut payload, KIRO_ACCOUNT_STATUS_ERROR, Some(reason));
+            payload
+        }
+    }
+}
+
+/// 带超时的用量补全：超时时标记为 error，避免单个卡住的请求拖住整个导入
+pub async fn enrich_payload_with_runtime_usage_timeout(
+    payload: KiroOAuthCompletePayload,
+) -> KiroOAuthCompletePayload {
+    let enrich = enrich_payload_with_runtime_usage(payload.clone());
+    run_with_usage_timeout(
+        payload,
+        enrich,
+        std::time::Duration::from_secs(RUNTIME_USAGE_TIMEOUT_SECONDS),
+    )
+    .await
+}
+
+async fn enrich_payloads_with<F, Fut>(
+    payloads: Vec<KiroOAuthCompletePayload>,
+    enrich: F,
+    timeout: std::time::Duration,
+) -> Vec<KiroOAuthCompletePayload>
+where
+    F: Fn(KiroOAuthCompletePayload) -> Fut,
+    Fut: std::future::Future<Output = KiroOAuthCompletePayload>,
+{
+    use futures::future::join_all;
+    use tokio::sync::Semaphore;
+
+    let semaphore = Arc::new(Semaphore::new(KIRO_RUNTIME_MAX_CONCURRENT));
+    let tasks: Vec<_> = payloads
+        .into_iter()
+        .map(|payload| {
+            let semaphore = semaphore.clone();
+            let enrich = enrich(payload.clone());
+            async move {
+                let _permit = semaphore.acquire_owned().await.ok();
+                run_with_usage_timeout(payload, enrich, timeout).await
+            }
+        })
+        .collect();
+
+    join_all(tasks).await
+}
+
+/// 用量请求失败（非封禁）时保留导入时的状态，避免离线或临时故障把账号全部标记为 error
+async fn enrich_keeping_status_on_error<F, Fut>(
+    payload: KiroOAuthCompletePayload,
+    enrich: F,
+) -> KiroOAuthCompletePayload
+where
+    F: FnOnce(KiroOAuthCompletePayload) -> Fut,
+    Fut: std::future::Future<Output = KiroOAuthCompletePayload>,
+{
+    let status = payload.status.clone();
+    let status_reason = payload.status_reason.clone();
+    let mut enriched = enrich(payload).await;
+    if enriched.status.as_deref() == Some(KIRO_ACCOUNT_STATUS_ERROR) {
+        enriched.status = status;
+        enriched.status_reason = status_reason;
+    }
+    enriched
+}
+
+async fn enrich_imported_payload(payload: KiroOAuthCompletePayload) -> KiroOAuthCompletePayload {
+    enrich_keeping_status_on_error(payload, enrich_payload_with_runtime_usage).await
+}
+
+/// 批量补全导入账号：先按需刷新 token 再补全用量，限制并发并保持输入顺序，仅超时标记 error
+pub async fn refresh_imported_payloads(
+    payloads: Vec<KiroOAuthCompletePayload>,
+) -> Vec<KiroOAuthCompletePayload> {
+    enrich_payloads_with(
+        payloads,
+        |payload| refresh_payload_with(payload, now_timestamp(), enrich_imported_payload),
+        std::time::Duration::from_secs(RUNTIME_USAGE_TIMEOUT_SECONDS),
+    )
+    .await
+}
+
+/// 刷新失败且只能重新登录时（无 refresh token / 被拒绝）返回的原因，其余错误返回 None
+fn reauth_required_reason(err: &str) -> Option<String> {
+    let value = serde_json::from_str::<Value>(err).ok()?;
+    if value.get("error_type").and_then(Value::as_str) != Some("kiro_reauth_required") {
+        return None;
+    }
+    value
+        .get("message")
+        .and_then(Value::as_str)
+        .map(|message| message.to_string())
+}
+
+fn access_token_needs_refresh(expires_at: Option<i64>, now: i64) -> bool {
+    expires_at
+        .map(|value| value - now <= TOKEN_REFRESH_SKEW_SECONDS)
+        .unwrap_or(false)
+}
+
+fn access_token_expired(expires_at: Option<i64>, now: i64) -> bool {
+    expires_at.map(|value| value <= now).unwrap_or(false)
+}
+
+/// refresh token 的签发方，决定向哪个端点换取新 token
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TokenRefreshRoute {
+    /// 社交登录：Kiro 认证服务
+    KiroSocial,
+    /// BuilderId/Enterprise：AWS SSO OIDC
+    AwsOidc,
+    /// 组织自有 IdP：IdP 的 token_endpoint
+    ExternalIdp,
+}
+
+fn token_refresh_route(payload: &KiroOAuthCompletePayload) -> TokenRefreshRoute {
+    let auth_method = pick_string(payload.kiro_auth_token_raw.as_ref(), &[&["authMethod"]]);
+    if let Some(method) = auth_method {
+        if method.eq_ignore_ascii_case("idc") {
+            return TokenRefreshRoute::AwsOidc;
+        }
+        if method.eq_ignore_ascii_case("external_idp") {
+            return TokenRefreshRoute::ExternalIdp;
+        }
+        return TokenRefreshRoute::KiroSocial;
+    }
+    match payload.login_provider.as_deref() {
+        Some("BuilderId") | Some("Enterprise") => TokenRefreshRoute::AwsOidc,
+        Some("ExternalIdp") => TokenRefreshRoute::ExternalIdp,
+        _ => TokenRefreshRoute::KiroSocial,
+    }
+}
+
+/// 读取 Kiro 客户端写在 `~/.aws/sso/cache/<clientIdHash>.json` 的 OIDC 客户端注册信息
+fn read_client_registration(
+    cache_dir: &std::path::Path,
+    client_id_hash: &str,
+) -> Option<(String, String)> {
+    if client_id_hash.is_empty() || !client_id_hash.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let content = fs::read_to_string(cache_dir.join(format!("{}.json", client_id_hash))).ok()?;
+    let parsed = serde_json::from_str::<Value>(&content).ok()?;
+    let client_id = pick_string(Some(&parsed), &[&["clientId"]])?;
+    let client_secret = pick_string(Some(&parsed), &[&["clientSecret"]])?;
+    Some((client_id, client_secret))
+}
+
+fn load_local_client_registration(raw: Option<&Value>) -> Option<(String, String)> {
+    let client_id_hash = pick_string(raw, &[&["clientIdHash"]])?;
+    let token_path = kiro_account::get_default_kiro_auth_token_path().ok()?;
+    read_client_registration(token_path.parent()?, &client_id_hash)
+}
+
+/// 仅 OIDC `invalid_grant`/`invalid_client`（客户端注册过期）与 Kiro 认证服务 401/403 视为需重新登录
+fn is_refresh_token_rejected(route: TokenRefreshRoute, status: u16, body: &str) -> bool {
+    if route == TokenRefreshRoute::KiroSocial {
+        return matches!(status, 401 | 403);
+    }
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| pick_string(Some(&value), &[&["error"]]))
+        .map(|error| matches!(error.as_str(), "invalid_grant" | "invalid_client"))
+        .unwrap_or(false)
+}
+
+async fn request_token_refresh(
+    request: reqwest::RequestBuilder,
+    route: TokenRefreshRoute,
+) -> Result<Value, String> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Kiro token refresh request failed: {}", e))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if is_refresh_token_rejected(route, status.as_u16(), &body) {
+        return Err(crate::error::kiro_reauth_required_error(&format!(
+            "Refresh token was rejected (status={}), please log in again",
+            status.as_u16()
+        )));
+    }
+    if !status.is_success() {
+        return Err(format!(
+            "Kiro token refresh failed: status={}, body={}",
+            status.as_u16(),
+            body
+        ));
+    }
+    serde_json::from_str::<Value>(&body)
+        .map(unwrap_token_response)
+        .map_err(|e| format!("Failed to parse Kiro token refresh response: {}", e))
+}
+
+fn merge_refreshed_token(
+    payload: &mut KiroOAuthCompletePayload,
+    token: &Value,
+    now: i64,
+) -> Result<(), String> {
+    let access_token = pick_string(Some(token), &[&["accessToken"], &["access_token"]])
+        .ok_or_else(|| "Kiro token refresh response missing accessToken".to_string())?;
+    let refresh_token = pick_string(Some(token), &[&["refreshToken"], &["refresh_token"]]);
+    let expires_at = parse_timestamp(
+        get_path_value(token, &["expiresAt"]).or_else(|| get_path_value(token, &["expires_at"])),
+    )
+    .or_else(|| {
+        pick_number(Some(token), &[&["expiresIn"], &["expires_in"]])
+            .filter(|seconds| *seconds > 0.0)
+            .map(|seconds| now + seconds as i64)
+    });
+    let profile_arn = pick_string(Some(token), &[&["profileArn"]]);
+
+    payload.access_token = access_token.clone();
+    if let Some(value) = refresh_token.clone() {
+        payload.refresh_token = Some(value);
+    }
+    if expires_at.is_some() {
+        payload.expires_at = expires_at;
+    }
+
+    let mut raw = payload
+        .kiro_auth_token_raw
+        .take()
+        .filter(|value| value.is_object())
+        .unwrap_or_else(|| json!({}));
+    if let Some(obj) = raw.as_object_mut() {
+        obj.insert("accessToken".to_string(), Value::String(access_token));
+        if let Some(value) = refresh_token {
+            obj.insert("refreshToken".to_string(), Value::String(value));
+        }
+        if let Some(dt) = expires_at.and_then(|value| chrono::DateTime::from_timestamp(value, 0)) {
+            obj.insert("expiresAt".to_string(), Value::String(dt.to_rfc3339()));
+        }
+        if let Some(value) = profile_arn {
+            obj.insert("profileArn".to_string(), Value::String(value));
+        }
+    }
+    payload.kiro_auth_token_raw = Some(raw);
+    Ok(())
+}
+
+/// 用 refresh token 换取新的 access token（社交登录走 Kiro 认证服务，BuilderId/Enterprise 走 OIDC，
+/// External IdP 走 IdP 自身的令牌端点）
+pub async fn refresh_kiro_access_token(
+    payload: &mut KiroOAuthCompletePayload,
+) -> Result<(), String> {
+    let refresh_token = normalize_non_empty(payload.refresh_token.as_deref()).ok_or_else(|| {
+        crate::error::kiro_reauth_required_error(
+            "Account has no refresh token, please log in again",
+        )
+    })?;
+
+    let route = token_refresh_route(payload);
+    let mut discovered_endpoint = None;
+    let token = match route {
+        TokenRefreshRoute::AwsOidc => {
+            let raw = payload.kiro_auth_token_raw.as_ref();
+            let region = normalize_region_option(payload.idc_region.clone());
+            let stored_client_id = normalize_non_empty(payload.client_id.as_deref())
+                .or_else(|| pick_string(raw, &[&["clientId"], &["client_id"]]));
+            let stored_client_secret = normalize_non_empty(payload.client_secret.as_deref());
+            // 旧账号与本地导入的账号没有 clientSecret，回退到 Kiro 客户端的本地注册文件
+            let (client_id, client_secret) = match (stored_client_id, stored_client_secret) {
+                (Some(client_id), Some(client_secret)) => (client_id, client_secret),
+                _ => load_local_client_registration(raw).ok_or_else(|| {
+                    crate::error::kiro_reauth_required_error(
+                        "Account is missing OIDC client registration, please log in again",
+                    )
+                })?,
+            };
+            let request = reqwest::Client::new()
+                .post(format!("https://oidc.{}.amazonaws.com/token", region))
+                .json(&json!({
+                    "clientId": client_id,
+                    "clientSecret": client_secret,
+                    "grantType": "refresh_token",
+                    "refreshToken": refresh_token
+                }));
+            request_token_refresh(request, route).await?
+        }
+        TokenRefreshRoute::ExternalIdp => {
+            let raw = payload.kiro_auth_token_raw.as_ref();
+            let client_id = normalize_non_empty(payload.client_id.as_deref())
+                .or_else(|| pick_string(raw, &[&["client_id"], &["clientId"]]))
+                .ok_or_else(|| {
+                    crate::error::kiro_reauth_required_error(
+                        "Account is missing External IdP client_id, please log in again",
+                    )
+                })?;
+            let token_endpoint = match pick_string(raw, &[&["tokenEndpoint"], &["token_endpoint"]])
+            {
+                Some(endpoint) => endpoint,
+                None => {
+                    let issuer_url = normalize_non_empty(payload.issuer_url.as_deref())
+                        .or_else(|| pick_string(raw, &[&["issuer_url"], &["issuerUrl"]]))
+                        .ok_or_else(|| {
+                            crate::error::kiro_reauth_required_error(
+                                "Account is missing External IdP issuer_url, please log in again",
+                            )
+                        })?;
+                    let discovery = fetch_oidc_discovery(&issuer_url).await?;
+                    let endpoint = discovered_token_endpoint(&discovery).ok_or_else(|| {
+                        "External IdP discovery document has no https token_endpoint".to_string()
+                    })?;
+                    discovered_endpoint = Some(endpoint.clone());
+                    endpoint
+                }
+            };
+            let request = reqwest::Client::new().post(token_endpoint).form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", client_id.as_str()),
+                ("refresh_token", refresh_token.as_str()),
+            ]);
+            request_token_refresh(request, route).await?
+        }
+        TokenRefreshRoute::KiroSocial => {
+            let request = reqwest::Client::new()
+                .post(KIRO_REFRESH_TOKEN_ENDPOINT)
+                .json(&json!({ "refreshToken": refresh_token }));
+            request_token_refresh(request, route).await?
+        }
+    };
+
+    merge_refreshed_token(payload, &token, now_timestamp())?;
+    // 记下发现到的令牌端点，之后刷新无需再请求发现文档
+    if let Some(endpoint) = discovered_endpoint {
+        if let Some(obj) = payload
+            .kiro_auth_token_raw
+            .as_mut()
+            .and_then(Value::as_object_mut)
+        {
+            obj.insert("tokenEndpoint".to_string(), Value::String(endpoint));
+        }
+    }
+    Ok(())
+}
+
+async fn refresh_payload_with<E, Fut>(
+    mut payload: KiroOAuthCompletePayload,
+    now: i64,
+    enrich: E,
+) -> KiroOAuthCompletePayload
+where
+    E: FnOnce(KiroOAuthCompletePayload) -> Fut,
+    Fut: std::future::Future<Output = KiroOAuthCompletePayload>,
+{
+    // 仅在 access token 即将过期时换取新 token，其余情况仍交给 Kiro 客户端维护。
+    if access_token_needs_refresh(payload.expires_at, now) {
+        match refresh_kiro_access_token(&mut payload).await {
+            Ok(()) => logger::log_info(&format!(
+                "[Kiro Refresh] Access token refreshed: email={}",
+                payload.email
+            )),
+            Err(err) => match reauth_required_reason(&err) {
+                // access token 已过期时才要求重新登录，否则当前 token 仍可继续使用
+                Some(reason) if access_token_expired(payload.expires_at, now) => {
+                    logger::log_warn(&format!(
+                        "[Kiro Refresh] Re-login required: email={}, reason={}",
+                        payload.email, reason
+                    ));
+                    set_payload_status(&mut payload, KIRO_ACCOUNT_STATUS_ERROR, Some(reason));
+                    return payload;
+                }
+                Some(reason) => logger::log_warn(&format!(
+                    "[Kiro Refresh] Access token refresh failed, keeping current token: {}",
+                    reason
+                )),
+                None => logger::log_warn(&format!(
+                    "[Kiro Refresh] Access token refresh failed, keeping current token: {}",
+                    err
+                )),
+            },
+        }
+    }
+    enrich(payload).await
+}
+
+/// `allow_token_refresh` 为 false 时（当前/绑定账号）不做远程 refresh：
+/// 其 refresh token 与 Kiro 客户端本地缓存共用，一方轮换会让另一方失效，仍交给 Kiro 客户端维护。
 pub async fn refresh_payload_for_account(
     account: &KiroAccount,
+    allow_token_refresh: bool,
 ) -> Result<KiroOAuthCompletePayload, String> {
-    // 不执行远程 refreshToken：交给系统/Kiro 客户端维护 token。
-    Ok(enrich_payload_with_runtime_usage(payload_from_account(account)).await)
+    let payload = payload_from_account(account);
+    if !allow_token_refresh {
+        return Ok(enrich_payload_with_runtime_usage(payload).await);
+    }
+    Ok(refresh_payload_with(payload, now_timestamp(), enrich_payload_with_runtime_usage).await)
 }
 
 pub async fn start_login(options: Option<KiroOAuthStartOptions>) -> Result<KiroOAuthStartResponse, String> {
@@ -1657,6 +2269,7 @@ pub async fn start_login(options: Option<KiroOAuthStartOptions>) -> Result<KiroO
                 });
             }
             *guard = None;
+            clear_persisted_pending_state();
         }
     }
 
@@ -1706,9 +2319,7 @@ pub async fn start_login(options: Option<KiroOAuthStartOptions>) -> Result<KiroO
             callback_result: None,
         };
 
-        if let Ok(mut guard) = PENDING_OAUTH_STATE.lock() {
-            *guard = Some(pending.clone());
-        }
+        set_pending_state(&pending);
 
         return Ok(KiroOAuthStartResponse {
             login_id: pending.login_id,
@@ -1775,9 +2386,7 @@ pub async fn start_login(options: Option<KiroOAuthStartOptions>) -> Result<KiroO
             callback_result: None,
         };
 
-        if let Ok(mut guard) = PENDING_OAUTH_STATE.lock() {
-            *guard = Some(pending.clone());
-        }
+        set_pending_state(&pending);
 
         let expected_login_id = pending.login_id.clone();
         let expected_state = pending.state_token.clone();
@@ -1849,9 +2458,7 @@ pub async fn start_login(options: Option<KiroOAuthStartOptions>) -> Result<KiroO
         callback_result: None,
     };
 
-    if let Ok(mut guard) = PENDING_OAUTH_STATE.lock() {
-        *guard = Some(pending.clone());
-    }
+    set_pending_state(&pending);
 
     let expected_login_id = pending.login_id.clone();
     let expected_state = state_token.clone();
@@ -1949,7 +2556,8 @@ pub async fn complete_login(login_id: &str) -> Result<KiroOAuthCompletePayload,
                         .or_insert_with(|| Value::String(client_id.clone()));
                 }
                 let _ = cancel_login(Some(login_id));
-                let payload = build_payload_from_snapshot(token, None, None)?;
+                let mut payload = build_payload_from_snapshot(token, None, None)?;
+                payload.client_secret = Some(client_secret);
                 return Ok(enrich_payload_with_runtime_usage(payload).await);
             }
 
@@ -1964,7 +2572,7 @@ pub async fn complete_login(login_id: &str) -> Result<KiroOAuthCompletePayload,
             let _ = cancel_login(Some(login_id));
             let callback = result?;
 
-            let auth_token = if state.flow == OAuthFlow::IamSso {
+            let (auth_token, client_secret) = if state.flow == OAuthFlow::IamSso {
                 let region = state
                     .region
                     .clone()
@@ -2009,25 +2617,32 @@ pub async fn complete_login(login_id: &str) -> Result<KiroOAuthCompletePayload,
                     obj.entry("clientId".to_string())
                         .or_insert_with(|| Value::String(client_id.clone()));
                 }
-                token
+                (token, Some(client_secret))
             } else {
                 let login_option = callback.login_option.trim().to_ascii_lowercase();
                 if callback.code.is_none() {
-                    let reason = match login_option.as_str() {
+                    // 门户对这些登录方式不返回授权码，无法在此完成换取，只能引导用户走其他路径
+                    return Err(match login_option.as_str() {
                         "builderid" | "awsidc" | "internal" => {
-                            "Current login method requires Kiro client follow-up auth flow and is not supported for direct import. Please use BuilderId/Enterprise flow."
+                            crate::error::kiro_login_action_required_error(
+                                &login_option,
+                                "use_builder_id_flow",
+                                "Current login method requires Kiro client follow-up auth flow and is not supported for direct import. Please use BuilderId/Enterprise flow.",
+                            )
                         }
                         "external_idp" => {
-                            "Current login method is External IdP and no authorization code was returned. Automatic import is not supported."
+                            external_idp_login_error(login_id, &login_option, &callback).await
                         }
-                        _ => "Callback missing authorization code, cannot complete login.",
-                    };
-                    return Err(reason.to_string());
+                        _ => "Callback missing authorization code, cannot complete login.".to_string(),
+                    });
                 }
                 let redirect_uri = build_token_exchange_redirect_uri(&state.callback_url, &callback);
-                exchange_code_for_token(&callback, &state.code_verifier, &redirect_uri).await?
+                let token =
+                    exchange_code_for_token(&callback, &state.code_verifier, &redirect_uri).await?;
+                (token, None)
             };
-            let payload = build_payload_from_snapshot(auth_token, None, None)?;
+            let mut payload = build_payload_from_snapshot(auth_token, None, None)?;
+            payload.client_secret = client_secret;
             return Ok(enrich_payload_with_runtime_usage(payload).await);
         }
 
@@ -2049,6 +2664,7 @@ pub fn cancel_login(login_id: Option<&str>) -> Result<(), String> {
         }
         (None, _) => {}
     }
+    clear_persisted_pending_state();
     Ok(())
 }
 
@@ -2082,7 +2698,7 @@ pub async fn build_payload_from_token(token: &str) -> Result<KiroOAuthCompletePa
     }
 
     let payload = build_payload_from_snapshot(snapshot, None, None)?;
-    Ok(enrich_payload_with_runtime_usage(payload).await)
+    Ok(enrich_payload_with_runtime_usage_timeout(payload).await)
 }
 
 #[cfg(test)]
@@ -2162,5 +2778,459 @@ mod tests {
             "bonus_expire_days should derive from freeTrialExpiry"
         );
     }
+
+    #[test]
+    fn merge_refreshed_token_updates_payload_and_raw_snapshot() {
+        let mut payload = build_payload_from_snapshot(
+            json!({
+                "accessToken": "old_access",
+                "refreshToken": "old_refresh",
+                "expiresAt": "2026-01-01T00:00:00Z",
+                "provider": "Github"
+            }),
+            None,
+            None,
+        )
+        .expect("payload should parse");
+
+        merge_refreshed_token(
+            &mut payload,
+            &json!({ "accessToken": "new_access", "expiresIn": 3600 }),
+            1_000,
+        )
+        .expect("merge should succeed");
+
+        assert_eq!(payload.access_token, "new_access");
+        assert_eq!(payload.refresh_token.as_deref(), Some("old_refresh"));
+        assert_eq!(payload.expires_at, Some(4_600));
+        let raw = payload.kiro_auth_token_raw.expect("raw token kept");
+        assert_eq!(raw["accessToken"], "new_access");
+        assert_eq!(raw["refreshToken"], "old_refresh");
+        assert_eq!(raw["provider"], "Github");
+
+        let mut payload = payload_without_refresh_token();
+        assert!(merge_refreshed_token(&mut payload, &json!({}), 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_without_refresh_token_requires_reauth() {
+        let mut payload = payload_without_refresh_token();
+        let err = refresh_kiro_access_token(&mut payload)
+            .await
+            .expect_err("should fail without refresh token");
+        assert_eq!(
+            reauth_required_reason(&err).as_deref(),
+            Some("Account has no refresh token, please log in again")
+        );
+    }
+
+    fn idc_payload_without_client_secret(expires_at: i64) -> KiroOAuthCompletePayload {
+        let mut payload = build_payload_from_snapshot(
+            json!({
+                "accessToken": "access",
+                "refreshToken": "refresh",
+                "authMethod": "IdC",
+                "clientId": "client"
+            }),
+            None,
+            None,
+        )
+        .expect("payload should parse");
+        payload.email = "idc@example.com".to_string();
+        payload.expires_at = Some(expires_at);
+        payload
+    }
+
+    fn mark_enriched(mut payload: KiroOAuthCompletePayload) -> KiroOAuthCompletePayload {
+        set_payload_status(&mut payload, KIRO_ACCOUNT_STATUS_NORMAL, None);
+        payload
+    }
+
+    #[tokio::test]
+    async fn unexpired_token_without_client_secret_is_still_enriched() {
+        let payload = idc_payload_without_client_secret(1_060);
+        let result =
+            refresh_payload_with(
+                payload,
+                1_000,
+                |payload| async move { mark_enriched(payload) },
+            )
+            .await;
+        assert_eq!(result.status.as_deref(), Some(KIRO_ACCOUNT_STATUS_NORMAL));
+        assert_eq!(result.access_token, "access");
+    }
+
+    #[tokio::test]
+    async fn expired_token_without_client_secret_requires_reauth() {
+        let payload = idc_payload_without_client_secret(900);
+        let result =
+            refresh_payload_with(
+                payload,
+                1_000,
+                |payload| async move { mark_enriched(payload) },
+            )
+            .await;
+        assert_eq!(result.status.as_deref(), Some(KIRO_ACCOUNT_STATUS_ERROR));
+        assert_eq!(
+            result.status_reason.as_deref(),
+            Some("Account is missing OIDC client registration, please log in again")
+        );
+    }
+
+    #[test]
+    fn only_auth_errors_reject_refresh_token() {
+        for route in [TokenRefreshRoute::AwsOidc, TokenRefreshRoute::ExternalIdp] {
+            assert!(is_refresh_token_rejected(
+                route,
+                400,
+                r#"{"error":"invalid_grant","error_description":"expired"}"#
+            ));
+            assert!(is_refresh_token_rejected(
+                route,
+                400,
+                r#"{"error":"invalid_client"}"#
+            ));
+            assert!(!is_refresh_token_rejected(
+                route,
+                400,
+                r#"{"error":"invalid_request"}"#
+            ));
+            assert!(!is_refresh_token_rejected(route, 401, "unauthorized"));
+        }
+        let route = TokenRefreshRoute::KiroSocial;
+        assert!(is_refresh_token_rejected(route, 401, ""));
+        assert!(is_refresh_token_rejected(route, 403, ""));
+        assert!(!is_refresh_token_rejected(route, 400, "bad request"));
+    }
+
+    fn payload_with_auth_method(auth_method: &str) -> KiroOAuthCompletePayload {
+        build_payload_from_snapshot(
+            json!({
+                "accessToken": "access",
+                "refreshToken": "refresh",
+                "authMethod": auth_method
+            }),
+            None,
+            None,
+        )
+        .expect("payload should parse")
+    }
+
+    #[test]
+    fn client_secret_is_kept_out_of_raw_snapshot() {
+        let payload = build_payload_from_snapshot(
+            json!({
+                "accessToken": "access",
+                "authMethod": "IdC",
+                "clientId": "client",
+                "clientSecret": "secret"
+            }),
+            None,
+            None,
+        )
+        .expect("payload should parse");
+        assert_eq!(payload.client_secret.as_deref(), Some("secret"));
+        let raw = payload.kiro_auth_token_raw.expect("raw token kept");
+        assert!(raw.get("clientSecret").is_none());
+        assert_eq!(raw["clientId"], "client");
+    }
+
+    #[test]
+    fn token_refresh_route_follows_auth_method() {
+        assert_eq!(
+            token_refresh_route(&payload_with_auth_method("external_idp")),
+            TokenRefreshRoute::ExternalIdp
+        );
+        assert_eq!(
+            token_refresh_route(&payload_with_auth_method("IdC")),
+            TokenRefreshRoute::AwsOidc
+        );
+        assert_eq!(
+            token_refresh_route(&payload_with_auth_method("social")),
+            TokenRefreshRoute::KiroSocial
+        );
+    }
+
+    #[tokio::test]
+    async fn external_idp_refresh_posts_refresh_grant_to_idp_token_endpoint() {
+        let (base_url, request_body) =
+            serve_once(r#"{"access_token":"idp_access","expires_in":3600}"#);
+        let mut payload = build_payload_from_snapshot(
+            json!({
+                "accessToken": "access",
+                "refreshToken": "idp_refresh",
+                "authMethod": "external_idp",
+                "client_id": "client-1",
+                "tokenEndpoint": format!("{}/token", base_url)
+            }),
+            None,
+            None,
+        )
+        .expect("payload should parse");
+
+        refresh_kiro_access_token(&mut payload)
+            .await
+            .expect("refresh should succeed");
+
+        assert_eq!(payload.access_token, "idp_access");
+        let params: HashMap<String, String> =
+            url::form_urlencoded::parse(request_body.recv().expect("request body").as_bytes())
+                .into_owned()
+                .collect();
+        assert_eq!(
+            params.get("grant_type").map(String::as_str),
+            Some("refresh_token")
+        );
+        assert_eq!(
+            params.get("client_id").map(String::as_str),
+            Some("client-1")
+        );
+        assert_eq!(
+            params.get("refresh_token").map(String::as_str),
+            Some("idp_refresh")
+        );
+    }
+
+    #[test]
+    fn client_registration_is_read_by_client_id_hash() {
+        let dir = std::env::temp_dir().join(format!("kiro_registration_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        fs::write(
+            dir.join("abc123.json"),
+            r#"{"clientId":"client","clientSecret":"secret"}"#,
+        )
+        .expect("write registration");
+
+        assert_eq!(
+            read_client_registration(&dir, "abc123"),
+            Some(("client".to_string(), "secret".to_string()))
+        );
+        assert_eq!(read_client_registration(&dir, "missing"), None);
+        assert_eq!(read_client_registration(&dir, "../abc123"), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn access_token_refresh_window() {
+        assert!(access_token_needs_refresh(Some(1_100), 1_000));
+        assert!(!access_token_needs_refresh(Some(10_000), 1_000));
+        assert!(!access_token_needs_refresh(None, 1_000));
+    }
+
+    fn payload_without_refresh_token() -> KiroOAuthCompletePayload {
+        build_payload_from_snapshot(json!({ "accessToken": "access" }), None, None)
+            .expect("payload should parse")
+    }
+
+    fn payload_with_email(email: &str) -> KiroOAuthCompletePayload {
+        let mut payload = payload_without_refresh_token();
+        payload.email = email.to_string();
+        payload
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn usage_timeout_marks_hung_fetch_as_error() {
+        let payload = payload_with_email("hung@example.com");
+        let result = run_with_usage_timeout(
+            payload,
+            std::future::pending::<KiroOAuthCompletePayload>(),
+            std::time::Duration::from_secs(RUNTIME_USAGE_TIMEOUT_SECONDS),
+        )
+        .await;
+
+        assert_eq!(result.email, "hung@example.com");
+        assert_eq!(result.status.as_deref(), Some(KIRO_ACCOUNT_STATUS_ERROR));
+        assert!(result
+            .status_reason
+            .as_deref()
+            .unwrap_or_default()
+            .contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn import_enrichment_keeps_imported_status_on_request_failure() {
+        let mut payload = payload_with_email("offline@example.com");
+        set_payload_status(&mut payload, KIRO_ACCOUNT_STATUS_NORMAL, None);
+        let result = enrich_keeping_status_on_error(payload, |mut payload| async move {
+            set_payload_status(
+                &mut payload,
+                KIRO_ACCOUNT_STATUS_ERROR,
+                Some("Failed to request Kiro runtime usage".to_string()),
+            );
+            payload
+        })
+        .await;
+        assert_eq!(result.status.as_deref(), Some(KIRO_ACCOUNT_STATUS_NORMAL));
+        assert_eq!(result.status_reason, None);
+
+        let result = enrich_keeping_status_on_error(result, |mut payload| async move {
+            set_payload_status(
+                &mut payload,
+                KIRO_ACCOUNT_STATUS_BANNED,
+                Some("suspended".to_string()),
+            );
+            payload
+        })
+        .await;
+        assert_eq!(result.status.as_deref(), Some(KIRO_ACCOUNT_STATUS_BANNED));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_enrichment_keeps_order_and_bounds_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let total = KIRO_RUNTIME_MAX_CONCURRENT * 2 + 1;
+        let payloads: Vec<_> = (0..total)
+            .map(|idx| payload_with_email(&format!("user{}@example.com", idx)))
+            .collect();
+
+        let results = enrich_payloads_with(
+            payloads,
+            |mut payload| {
+                let in_flight = in_flight.clone();
+                let peak = peak.clone();
+                async move {
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(current, Ordering::SeqCst);
+                    // 越靠前的账号越慢完成，验证输出仍保持输入顺序
+                    let idx: u64 = payload
+                        .email
+                        .trim_start_matches("user")
+                        .trim_end_matches("@example.com")
+                        .parse()
+                        .unwrap_or(0);
+                    tokio::time::sleep(std::time::Duration::from_secs(20 - idx)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    payload.status = Some(KIRO_ACCOUNT_STATUS_NORMAL.to_string());
+                    payload
+                }
+            },
+            std::time::Duration::from_secs(60),
+        )
+        .await;
+
+        let emails: Vec<_> = results.iter().map(|p| p.email.clone()).collect();
+        let expected: Vec<_> = (0..total)
+            .map(|idx| format!("user{}@example.com", idx))
+            .collect();
+        assert_eq!(emails, expected);
+        assert!(results
+            .iter()
+            .all(|p| p.status.as_deref() == Some(KIRO_ACCOUNT_STATUS_NORMAL)));
+        assert_eq!(peak.load(Ordering::SeqCst), KIRO_RUNTIME_MAX_CONCURRENT);
+    }
+
+    fn external_idp_callback(client_id: Option<&str>) -> OAuthCallbackData {
+        OAuthCallbackData {
+            login_option: "external_idp".to_string(),
+            code: None,
+            issuer_url: Some("https://login.example.com/tenant".to_string()),
+            idc_region: None,
+            path: "/signin/callback".to_string(),
+            client_id: client_id.map(str::to_string),
+            scopes: Some("openid,profile,offline_access".to_string()),
+            login_hint: Some("dev@example.com".to_string()),
+            audience: Some("kiro".to_string()),
+        }
+    }
+
+    /// 在本地起一次性 HTTP 服务，返回其地址与收到的请求体
+    fn serve_once(body: &'static str) -> (String, std::sync::mpsc::Receiver<String>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").expect("bind test server");
+        let port = server
+            .server_addr()
+            .to_ip()
+            .map(|addr| addr.port())
+            .expect("ip listen addr");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok(mut request) = server.recv() {
+                let mut request_body = String::new();
+                let _ = std::io::Read::read_to_string(request.as_reader(), &mut request_body);
+                let _ = sender.send(request_body);
+                let _ = request.respond(tiny_http::Response::from_string(body));
+            }
+        });
+        (format!("http://127.0.0.1:{}", port), receiver)
+    }
+
+    #[tokio::test]
+    async fn external_idp_without_code_always_returns_open_kiro_client() {
+        let (issuer_url, _) = serve_once(
+            r#"{"authorization_endpoint":"https://login.example.com/authorize","token_endpoint":"https://login.example.com/token"}"#,
+        );
+        let mut callback = external_idp_callback(Some("client-1"));
+        callback.issuer_url = Some(issuer_url);
+        let mut without_issuer = external_idp_callback(None);
+        without_issuer.issuer_url = None;
+
+        // 无论发现文档是否可达，都不在本地继续 IdP 授权
+        for callback in [callback, without_issuer] {
+            let error: Value = serde_json::from_str(
+                &external_idp_login_error("login-1", "external_idp", &callback).await,
+            )
+            .expect("error is json");
+            assert_eq!(error["error_type"], "kiro_login_action_required");
+            assert_eq!(error["next_action"], "open_kiro_client");
+        }
+    }
+
+    #[test]
+    fn discovered_token_endpoint_requires_https() {
+        assert_eq!(
+            discovered_token_endpoint(
+                &json!({ "token_endpoint": "https://login.example.com/token" })
+            )
+            .as_deref(),
+            Some("https://login.example.com/token")
+        );
+        assert!(discovered_token_endpoint(
+            &json!({ "token_endpoint": "http://login.example.com/token" })
+        )
+        .is_none());
+        assert!(discovered_token_endpoint(&json!({})).is_none());
+    }
+
+    fn sample_pending_state(expires_at: i64) -> PendingOAuthState {
+        PendingOAuthState {
+            flow: OAuthFlow::Portal,
+            login_id: "login-1".to_string(),
+            expires_at,
+            verification_uri: KIRO_AUTH_PORTAL_URL.to_string(),
+            verification_uri_complete: format!("{}?state=abc", KIRO_AUTH_PORTAL_URL),
+            callback_url: "http://localhost:3128".to_string(),
+            callback_port: 3128,
+            state_token: "state-abc".to_string(),
+            code_verifier: "verifier".to_string(),
+            region: None,
+            client_id: None,
+            client_secret: None,
+            device_code: None,
+            interval_seconds: 1,
+            callback_result: Some(Err("stale".to_string())),
+        }
+    }
+
+    #[test]
+    fn pending_state_roundtrips_without_callback_result() {
+        let raw = serde_json::to_string(&sample_pending_state(2_000)).expect("serialize");
+        let restored = parse_restorable_pending_state(&raw, 1_000).expect("should restore");
+
+        assert_eq!(restored.flow, OAuthFlow::Portal);
+        assert_eq!(restored.login_id, "login-1");
+        assert_eq!(restored.callback_port, 3128);
+        assert_eq!(restored.state_token, "state-abc");
+        assert_eq!(restored.code_verifier, "verifier");
+        assert!(restored.callback_result.is_none());
+    }
+
+    #[test]
+    fn pending_state_is_discarded_when_expired_or_corrupt() {
+        let raw = serde_json::to_string(&sample_pending_state(1_000)).expect("serialize");
+        assert!(parse_restorable_pending_state(&raw, 1_000).is_none());
+        assert!(parse_restorable_pending_state("{not json", 0).is_none());
+    }
 }
 
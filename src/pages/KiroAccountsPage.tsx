@@ -147,6 +147,7 @@ export function KiroAccountsPage() {
   const [oauthMeta, setOauthMeta] = useState<{ expiresIn: number; intervalSeconds: number } | null>(null);
   const [oauthPrepareError, setOauthPrepareError] = useState<string | null>(null);
   const [oauthCompleteError, setOauthCompleteError] = useState<string | null>(null);
+  const [oauthNextAction, setOauthNextAction] = useState<string | null>(null);
   const [oauthPolling, setOauthPolling] = useState(false);
   const [oauthTimedOut, setOauthTimedOut] = useState(false);
   const [oauthProvider, setOauthProvider] = useState<'google' | 'github' | 'builderid' | 'awsidc' | 'enterprise'>('google');
@@ -275,14 +276,18 @@ export function KiroAccountsPage() {
   }, [fetchAccounts, t, oauthLog]);
 
   const handleOauthCompleteError = useCallback((e: unknown) => {
-    const msg = String(e).replace(/^Error:\s*/, '');
+    const actionRequired = kiroService.parseKiroLoginActionRequired(e);
+    const msg = actionRequired
+      ? t(`kiro.oauth.nextAction.${actionRequired.nextAction}`, actionRequired.message)
+      : String(e).replace(/^Error:\s*/, '');
     setOauthCompleteError(msg);
+    setOauthNextAction(actionRequired?.nextAction ?? null);
     setOauthTimedOut(/超时|过期|expired|timeout/i.test(msg));
     setOauthPolling(false);
     oauthCompletingRef.current = false;
     oauthActiveRef.current = false;
     oauthLog('Kiro OAuth Authorization failed', { loginId: oauthLoginIdRef.current, error: msg });
-  }, [oauthLog]);
+  }, [oauthLog, t]);
 
   const prepareOauthUrl = useCallback(() => {
     if (!showAddModalRef.current || addTabRef.current !== 'oauth') return;
@@ -291,6 +296,7 @@ export function KiroAccountsPage() {
     oauthActiveRef.current = true;
     setOauthPrepareError(null);
     setOauthCompleteError(null);
+    setOauthNextAction(null);
     setOauthTimedOut(false);
     setOauthPolling(false);
     setOauthUrlCopied(false);
@@ -370,6 +376,7 @@ export function KiroAccountsPage() {
     setOauthMeta(null);
     setOauthPrepareError(null);
     setOauthCompleteError(null);
+    setOauthNextAction(null);
     setOauthTimedOut(false);
     setOauthPolling(false);
   }, [showAddModal, addTab, oauthLog]);
@@ -458,6 +465,7 @@ export function KiroAccountsPage() {
     setOauthMeta(null);
     setOauthPrepareError(null);
     setOauthCompleteError(null);
+    setOauthNextAction(null);
     setOauthTimedOut(false);
     setOauthPolling(false);
     oauthActiveRef.current = false;
@@ -538,13 +546,23 @@ export function KiroAccountsPage() {
       }, 1200);
     } catch (e) {
       setAddStatus('error');
-      const errorMsg = String(e).replace(/^Error:\s*/, '');
-      setAddMessage(
-        t('common.shared.import.failedMsg', {
-          error: errorMsg,
-          defaultValue: '导入失败: {{error}}',
-        })
-      );
+      if (kiroService.isKiroLocalAuthNotFoundError(e)) {
+        setAddTab('token');
+        setAddMessage(
+          t(
+            'kiro.import.localNotFound',
+            '未在本机找到 Kiro 登录信息，请粘贴 Access Token 导入'
+          )
+        );
+      } else {
+        const errorMsg = String(e).replace(/^Error:\s*/, '');
+        setAddMessage(
+          t('common.shared.import.failedMsg', {
+            error: errorMsg,
+            defaultValue: '导入失败: {{error}}',
+          })
+        );
+      }
     }
     setImporting(false);
   };
@@ -633,6 +651,7 @@ export function KiroAccountsPage() {
     oauthCompletingRef.current = false;
     setOauthPrepareError(null);
     setOauthCompleteError(null);
+    setOauthNextAction(null);
     setOauthTimedOut(false);
     setOauthPolling(false);
     setOauthMeta(null);
@@ -1928,6 +1947,7 @@ export function KiroAccountsPage() {
                             setOauthProvider(e.target.value as 'google' | 'github' | 'builderid' | 'awsidc' | 'enterprise');
                             setOauthPrepareError(null);
                             setOauthCompleteError(null);
+                            setOauthNextAction(null);
                             setOauthTimedOut(false);
                             setOauthPolling(false);
                             setOauthMeta(null);
@@ -2060,6 +2080,11 @@ export function KiroAccountsPage() {
                         <div className="add-status error">
                           <CircleAlert size={16} />
                           <span>{oauthCompleteError}</span>
+                          {oauthNextAction === 'open_kiro_client' && (
+                            <button className="btn btn-sm btn-outline" onClick={() => openAddModal('import')}>
+                              {t('kiro.import.localClient', 'Import from local Kiro')}
+                            </button>
+                          )}
                           {oauthTimedOut && (
                             <button className="btn btn-sm btn-outline" onClick={handleRetryOauth}>
                               {t('common.shared.oauth.timeoutRetry', 'Refresh authorization link')}
@@ -42,6 +42,37 @@ export async function importKiroFromLocal(): Promise<KiroAccount[]> {
   return await invoke('import_kiro_from_local');
 }
 
+/** 本机未找到 Kiro 授权文件（需改用 Token 导入），区别于文件解析失败 */
+export function isKiroLocalAuthNotFoundError(error: unknown): boolean {
+  try {
+    const parsed = typeof error === 'string' ? JSON.parse(error) : error;
+    return (parsed as { error_type?: string } | null)?.error_type === 'kiro_local_auth_not_found';
+  } catch {
+    return false;
+  }
+}
+
+export interface KiroLoginActionRequired {
+  loginOption: string;
+  nextAction: string;
+  message: string;
+}
+
+/** 解析需要用户后续操作的 OAuth 登录错误（如 External IdP） */
+export function parseKiroLoginActionRequired(error: unknown): KiroLoginActionRequired | null {
+  try {
+    const parsed = typeof error === 'string' ? JSON.parse(error) : error;
+    if (parsed?.error_type !== 'kiro_login_action_required') return null;
+    return {
+      loginOption: String(parsed.login_option ?? ''),
+      nextAction: String(parsed.next_action ?? ''),
+      message: String(parsed.message ?? ''),
+    };
+  } catch {
+    return null;
+  }
+}
+
 /** 导出 Kiro 账号 */
 export async function exportKiroAccounts(accountIds: string[]): Promise<string> {
   return await invoke('export_kiro_accounts', { accountIds });